@@ -1,8 +1,9 @@
 /// This crate provides a reader that decodes arbitrary text encodings into
-/// utf-8 to interoperate with standard rust text types
+/// utf-8, and a writer that encodes utf-8 back into arbitrary text
+/// encodings, to interoperate with standard rust text types
 extern crate encoding_rs;
 
 pub mod reader;
-// TODO: pub mod writer;
+pub mod writer;
 
 pub const DEFAULT_BUF_SIZE: usize = 4096;
@@ -3,12 +3,20 @@ use std::fmt;
 use std::io;
 use std::io::BufRead;
 use std::io::Read;
+use std::str;
 
-pub fn decoder_helper(decoder: &mut encoding_rs::Decoder, input: &[u8]) -> io::Result<String> {
-    let mut decoded = String::with_capacity(input.len() * 4);
+pub fn decoder_helper(
+    decoder: &mut encoding_rs::Decoder,
+    input: &[u8],
+    last: bool,
+) -> io::Result<String> {
+    // Even with no new input, a final `last` call can still need room to
+    // flush a single pending code point (e.g. a truncated trailing
+    // sequence), so the output buffer can't be sized on `input.len()` alone.
+    let mut decoded = String::with_capacity(cmp::max(input.len() * 4, 4));
 
     let (result, bytes_read) =
-        decoder.decode_to_string_without_replacement(&input, &mut decoded, false);
+        decoder.decode_to_string_without_replacement(&input, &mut decoded, last);
     if let encoding_rs::DecoderResult::Malformed(_, _) = result {
         Err(io::Error::new(
             io::ErrorKind::Other,
@@ -19,12 +27,447 @@ pub fn decoder_helper(decoder: &mut encoding_rs::Decoder, input: &[u8]) -> io::R
     }
 }
 
+/// Like `decoder_helper`, but decodes in lossy mode: malformed input is
+/// replaced with U+FFFD instead of aborting the stream.
+pub fn decoder_helper_lossy(decoder: &mut encoding_rs::Decoder, input: &[u8], last: bool) -> String {
+    let mut decoded = String::with_capacity(cmp::max(input.len() * 4, 4));
+    let _ = decoder.decode_to_string(&input, &mut decoded, last);
+    decoded
+}
+
+/// Wraps a reader and replays a handful of already-consumed bytes before
+/// resuming reads from it, so a caller can peek at the start of a stream
+/// (e.g. to sniff a BOM) without losing its position.
+pub struct BomPeekReader<R> {
+    inner: R,
+    peeked: Vec<u8>,
+    peeked_pos: usize,
+}
+
+impl<R: Read> Read for BomPeekReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.peeked_pos < self.peeked.len() {
+            let n = cmp::min(buf.len(), self.peeked.len() - self.peeked_pos);
+            buf[..n].copy_from_slice(&self.peeked[self.peeked_pos..self.peeked_pos + n]);
+            self.peeked_pos += n;
+            Ok(n)
+        } else {
+            self.inner.read(buf)
+        }
+    }
+}
+
+pub(crate) const BOM_UTF8: &[u8] = &[0xef, 0xbb, 0xbf];
+pub(crate) const BOM_UTF16LE: &[u8] = &[0xff, 0xfe];
+pub(crate) const BOM_UTF16BE: &[u8] = &[0xfe, 0xff];
+const BOM_UTF32LE: &[u8] = &[0xff, 0xfe, 0x00, 0x00];
+const BOM_UTF32BE: &[u8] = &[0x00, 0x00, 0xfe, 0xff];
+
+// How many leading bytes of a BOM-less stream to peek for an XML prolog's
+// `encoding="..."` attribute or an HTML `<meta charset="...">` tag.
+const DECLARATION_SNIFF_WINDOW: usize = 1024;
+
+/// Read up to `n` bytes from `reader` without assuming a single `read` call
+/// fills the buffer, stopping early at EOF. Used by every sniffing
+/// constructor that needs to peek the start of a stream.
+fn peek_up_to<R: Read>(reader: &mut R, n: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; n];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    buf.truncate(filled);
+    Ok(buf)
+}
+
+/// A byte-order-mark recognized at the start of a stream.
+#[derive(Clone, Copy)]
+enum DetectedBom {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Utf32Le,
+    Utf32Be,
+}
+
+/// Find the BOM (if any) that `bytes` starts with. The 4-byte UTF-32 BOMs
+/// are checked before the 2-byte UTF-16LE one, since `FF FE 00 00` (UTF-32LE)
+/// starts with the same two bytes as the UTF-16LE BOM `FF FE` and would
+/// otherwise be misdetected as UTF-16LE with a mangled `00 00` prefix.
+fn detect_bom(bytes: &[u8]) -> Option<DetectedBom> {
+    if bytes.starts_with(BOM_UTF32LE) {
+        Some(DetectedBom::Utf32Le)
+    } else if bytes.starts_with(BOM_UTF32BE) {
+        Some(DetectedBom::Utf32Be)
+    } else if bytes.starts_with(BOM_UTF8) {
+        Some(DetectedBom::Utf8)
+    } else if bytes.starts_with(BOM_UTF16LE) {
+        Some(DetectedBom::Utf16Le)
+    } else if bytes.starts_with(BOM_UTF16BE) {
+        Some(DetectedBom::Utf16Be)
+    } else {
+        None
+    }
+}
+
+/// Look for an XML `encoding="..."` declaration, or failing that an HTML
+/// `<meta charset="...">` tag, within the leading bytes of a stream, and
+/// return the declared label if one was found. This is a tentative,
+/// ASCII-compatible scan over raw bytes rather than a full decode: it works
+/// for every encoding_rs encoding except the UTF-16 family, whose ASCII
+/// characters aren't single bytes (those are only reachable via BOM).
+fn sniff_declared_encoding(bytes: &[u8]) -> Option<String> {
+    find_attr_value(bytes, b"encoding").or_else(|| find_attr_value(bytes, b"charset"))
+}
+
+/// Find `attr_name` in `haystack` and parse the `="..."` value that follows
+/// it. Since this is a plain byte search rather than a real tag parser, it
+/// can land on a false positive (an unrelated identifier ending in
+/// `attr_name`, or an occurrence with no `=...` after it, e.g. stray text
+/// earlier in the window). On a parse failure at one occurrence, keep
+/// searching for the next one instead of giving up.
+fn find_attr_value(haystack: &[u8], attr_name: &[u8]) -> Option<String> {
+    let mut search_start = 0;
+    while let Some(rel_pos) = find_subsequence(&haystack[search_start..], attr_name) {
+        let attr_pos = search_start + rel_pos;
+        // Require a non-identifier byte (or start-of-buffer) immediately
+        // before the match, so `data-textencoding=` doesn't look like `encoding=`.
+        let preceded_by_boundary = attr_pos
+            .checked_sub(1)
+            .and_then(|i| haystack.get(i))
+            .is_none_or(|&b| !(b.is_ascii_alphanumeric() || b == b'-' || b == b'_'));
+
+        if preceded_by_boundary {
+            if let Some(value) = parse_attr_value_at(haystack, attr_pos + attr_name.len()) {
+                return Some(value);
+            }
+        }
+        search_start = attr_pos + 1;
+    }
+    None
+}
+
+/// Parse an `=" ... "` (or `='...'`, or bare unquoted) attribute value
+/// starting at `pos`, which points just past the attribute name.
+fn parse_attr_value_at(haystack: &[u8], mut pos: usize) -> Option<String> {
+    let skip_whitespace = |haystack: &[u8], mut pos: usize| {
+        while haystack.get(pos).is_some_and(u8::is_ascii_whitespace) {
+            pos += 1;
+        }
+        pos
+    };
+
+    pos = skip_whitespace(haystack, pos);
+    if haystack.get(pos) != Some(&b'=') {
+        return None;
+    }
+    pos = skip_whitespace(haystack, pos + 1);
+
+    let quote = *haystack.get(pos)?;
+    let value_start;
+    let value_end;
+    if quote == b'"' || quote == b'\'' {
+        value_start = pos + 1;
+        value_end = value_start + haystack[value_start..].iter().position(|&b| b == quote)?;
+    } else {
+        value_start = pos;
+        value_end = haystack[value_start..]
+            .iter()
+            .position(|&b| b == b'>' || b.is_ascii_whitespace() || b == b';')
+            .map(|p| value_start + p)
+            .unwrap_or_else(|| haystack.len());
+    }
+
+    let value = &haystack[value_start..value_end];
+    if value.is_empty() || !value.iter().all(u8::is_ascii_graphic) {
+        return None;
+    }
+    str::from_utf8(value).ok().map(|s| s.to_string())
+}
+
+fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[derive(Clone, Copy)]
+enum Utf32ByteOrder {
+    Le,
+    Be,
+}
+
+/// `encoding_rs` has no UTF-32 support, so utf-32le/utf-32be/utf-32 are
+/// decoded by this hand-written, 4-bytes-at-a-time codec instead.
+struct Utf32Decoder {
+    byte_order: Utf32ByteOrder,
+    // 1-3 trailing bytes that didn't complete a code point in the last pass
+    pending: Vec<u8>,
+}
+
+impl Utf32Decoder {
+    fn new(byte_order: Utf32ByteOrder) -> Self {
+        Utf32Decoder {
+            byte_order,
+            pending: Vec::new(),
+        }
+    }
+}
+
+/// Decode a chunk of UTF-32 input. In lossy mode, an invalid code point or a
+/// truncated trailing sequence at `last` is replaced with U+FFFD instead of
+/// aborting the stream, matching the `encoding_rs`-backed decoders' lossy
+/// behavior.
+fn decode_utf32(state: &mut Utf32Decoder, input: &[u8], last: bool, lossy: bool) -> io::Result<String> {
+    let mut buf = Vec::with_capacity(state.pending.len() + input.len());
+    buf.extend_from_slice(&state.pending);
+    buf.extend_from_slice(input);
+
+    let mut decoded = String::with_capacity(buf.len());
+    let mut chunks = buf.chunks_exact(4);
+    for chunk in &mut chunks {
+        let scalar = match state.byte_order {
+            Utf32ByteOrder::Le => u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]),
+            Utf32ByteOrder::Be => u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]),
+        };
+        let is_surrogate = (0xd800..=0xdfff).contains(&scalar);
+        let decoded_char = if scalar > 0x10_ffff || is_surrogate {
+            None
+        } else {
+            char::from_u32(scalar)
+        };
+        match decoded_char {
+            Some(c) => decoded.push(c),
+            None if lossy => decoded.push('\u{fffd}'),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Malformed input. {:x?} is not a valid UTF-32 code point.", chunk),
+                ))
+            }
+        }
+    }
+
+    let remainder = chunks.remainder().to_vec();
+    if last && !remainder.is_empty() {
+        if lossy {
+            decoded.push('\u{fffd}');
+            state.pending = Vec::new();
+            return Ok(decoded);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "Malformed input. Truncated UTF-32 sequence {:x?} at end of stream.",
+                remainder
+            ),
+        ));
+    }
+    state.pending = remainder;
+    Ok(decoded)
+}
+
+/// The decode engine backing a `CodecReadBuffer`: either a delegate to
+/// `encoding_rs`, or the hand-written UTF-32 codec it doesn't provide.
+enum DecoderImpl {
+    EncodingRs(encoding_rs::Decoder),
+    Utf32(Utf32Decoder),
+}
+
+fn decode_step(
+    decoder_impl: &mut DecoderImpl,
+    input: &[u8],
+    last: bool,
+    lossy: bool,
+) -> io::Result<String> {
+    match *decoder_impl {
+        DecoderImpl::EncodingRs(ref mut decoder) => if lossy {
+            Ok(decoder_helper_lossy(decoder, input, last))
+        } else {
+            decoder_helper(decoder, input, last)
+        },
+        DecoderImpl::Utf32(ref mut state) => decode_utf32(state, input, last, lossy),
+    }
+}
+
 pub struct CodecReadBuffer<R> {
     inner: R,
-    decoder: encoding_rs::Decoder,
+    decoder: DecoderImpl,
     input_buf: Vec<u8>,
     output_buf: String,
     output_pos: usize,
+    lossy: bool,
+    // Set once the underlying reader has reported EOF and the decoder has
+    // been given its final `last` flush call. `encoding_rs` decoders panic
+    // if flushed a second time, so without this, a flush that produces
+    // non-empty output (e.g. a trailing lossy replacement character) would
+    // otherwise be followed by another, illegal flush call.
+    reached_eof: bool,
+}
+
+impl<R: Read> CodecReadBuffer<BomPeekReader<R>> {
+    /// Create a re-encoding buffered reader that sniffs a leading UTF-8 or
+    /// UTF-16 byte-order-mark to pick the encoding, falling back to
+    /// `default_encoding_name` when no BOM is present. Any BOM found is
+    /// stripped from the decoded output. The sniffed bytes are buffered and
+    /// replayed through the decoder, so the caller's reader position is
+    /// never lost.
+    pub fn with_bom_sniffing(inner: R, default_encoding_name: &str) -> io::Result<Self> {
+        Self::with_bom_sniffing_and_capacity(inner, default_encoding_name, ::DEFAULT_BUF_SIZE)
+    }
+
+    /// Create a BOM-sniffing re-encoding buffered reader with the specified
+    /// buffer capacity
+    pub fn with_bom_sniffing_and_capacity(
+        mut inner: R,
+        default_encoding_name: &str,
+        capacity: usize,
+    ) -> io::Result<Self> {
+        // 4 bytes is enough to hold the longest BOM (UTF-32) as well as every
+        // shorter one.
+        let peeked = peek_up_to(&mut inner, 4)?;
+        let detected_bom = detect_bom(&peeked);
+
+        let decoder = match detected_bom {
+            Some(DetectedBom::Utf32Le) => DecoderImpl::Utf32(Utf32Decoder::new(Utf32ByteOrder::Le)),
+            Some(DetectedBom::Utf32Be) => DecoderImpl::Utf32(Utf32Decoder::new(Utf32ByteOrder::Be)),
+            detected => {
+                let (encoding_name, bom_detected) = match detected {
+                    Some(DetectedBom::Utf8) => ("utf-8", true),
+                    Some(DetectedBom::Utf16Le) => ("utf-16le", true),
+                    Some(DetectedBom::Utf16Be) => ("utf-16be", true),
+                    Some(DetectedBom::Utf32Le) | Some(DetectedBom::Utf32Be) => unreachable!(),
+                    None => (default_encoding_name, false),
+                };
+                let decoder =
+                    encoding_rs::Encoding::for_label_no_replacement(encoding_name.as_bytes())
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::Other,
+                                format!("Unrecognized input encoding name: {}", encoding_name),
+                            )
+                        }).map(|enc| {
+                            if bom_detected {
+                                enc.new_decoder_with_bom_removal()
+                            } else {
+                                enc.new_decoder_without_bom_handling()
+                            }
+                        })?;
+                DecoderImpl::EncodingRs(decoder)
+            }
+        };
+
+        // The UTF-32 BOM is consumed here rather than replayed, since
+        // `DecoderImpl::Utf32` has no BOM-removal mode of its own; everything
+        // else is replayed through the decoder so a BOM it understands
+        // natively (or no BOM) passes through unchanged.
+        let replayed = match detected_bom {
+            Some(DetectedBom::Utf32Le) => peeked[BOM_UTF32LE.len()..].to_vec(),
+            Some(DetectedBom::Utf32Be) => peeked[BOM_UTF32BE.len()..].to_vec(),
+            _ => peeked,
+        };
+
+        Ok(CodecReadBuffer {
+            inner: BomPeekReader {
+                inner,
+                peeked: replayed,
+                peeked_pos: 0,
+            },
+            decoder,
+            input_buf: Vec::with_capacity(capacity),
+            output_buf: String::new(),
+            output_pos: 0,
+            lossy: false,
+            reached_eof: false,
+        })
+    }
+
+    /// Create a re-encoding buffered reader that, lacking a BOM, looks for an
+    /// `encoding="..."` attribute in an XML declaration or an HTML
+    /// `<meta charset="...">` tag to pick the encoding, falling back to
+    /// `default_encoding_name` if neither a BOM nor a declared encoding is
+    /// found. A BOM always takes precedence over a declared encoding. As
+    /// with `with_bom_sniffing`, the sniffed bytes are buffered and replayed
+    /// through the decoder, so the caller's reader position is never lost.
+    pub fn with_declaration_sniffing(inner: R, default_encoding_name: &str) -> io::Result<Self> {
+        Self::with_declaration_sniffing_and_capacity(
+            inner,
+            default_encoding_name,
+            ::DEFAULT_BUF_SIZE,
+        )
+    }
+
+    /// Create a declaration-sniffing re-encoding buffered reader with the
+    /// specified buffer capacity
+    pub fn with_declaration_sniffing_and_capacity(
+        mut inner: R,
+        default_encoding_name: &str,
+        capacity: usize,
+    ) -> io::Result<Self> {
+        let peek_buf = peek_up_to(&mut inner, DECLARATION_SNIFF_WINDOW)?;
+        let detected_bom = detect_bom(&peek_buf);
+
+        let decoder = match detected_bom {
+            Some(DetectedBom::Utf32Le) => DecoderImpl::Utf32(Utf32Decoder::new(Utf32ByteOrder::Le)),
+            Some(DetectedBom::Utf32Be) => DecoderImpl::Utf32(Utf32Decoder::new(Utf32ByteOrder::Be)),
+            detected => {
+                let (encoding_name, bom_detected) = match detected {
+                    Some(DetectedBom::Utf8) => ("utf-8".to_string(), true),
+                    Some(DetectedBom::Utf16Le) => ("utf-16le".to_string(), true),
+                    Some(DetectedBom::Utf16Be) => ("utf-16be".to_string(), true),
+                    Some(DetectedBom::Utf32Le) | Some(DetectedBom::Utf32Be) => unreachable!(),
+                    None => {
+                        if let Some(declared) = sniff_declared_encoding(&peek_buf) {
+                            (declared, false)
+                        } else {
+                            (default_encoding_name.to_string(), false)
+                        }
+                    }
+                };
+                let decoder = encoding_rs::Encoding::for_label_no_replacement(
+                    encoding_name.as_bytes(),
+                ).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("Unrecognized input encoding name: {}", encoding_name),
+                    )
+                }).map(|enc| {
+                    if bom_detected {
+                        enc.new_decoder_with_bom_removal()
+                    } else {
+                        enc.new_decoder_without_bom_handling()
+                    }
+                })?;
+                DecoderImpl::EncodingRs(decoder)
+            }
+        };
+
+        let replayed = match detected_bom {
+            Some(DetectedBom::Utf32Le) => peek_buf[BOM_UTF32LE.len()..].to_vec(),
+            Some(DetectedBom::Utf32Be) => peek_buf[BOM_UTF32BE.len()..].to_vec(),
+            _ => peek_buf,
+        };
+
+        Ok(CodecReadBuffer {
+            inner: BomPeekReader {
+                inner,
+                peeked: replayed,
+                peeked_pos: 0,
+            },
+            decoder,
+            input_buf: Vec::with_capacity(capacity),
+            output_buf: String::new(),
+            output_pos: 0,
+            lossy: false,
+            reached_eof: false,
+        })
+    }
 }
 
 impl<R: Read> CodecReadBuffer<R> {
@@ -43,17 +486,38 @@ impl<R: Read> CodecReadBuffer<R> {
     }
 
     pub fn for_encoding_with_initial_buffer(
-        inner: R,
+        mut inner: R,
         encoding_name: &str,
-        input_buf: Vec<u8>,
+        mut input_buf: Vec<u8>,
     ) -> io::Result<Self> {
-        let decoder = encoding_rs::Encoding::for_label_no_replacement(&encoding_name.as_bytes())
-            .ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Unrecognized input encoding name: {}", encoding_name),
-                )
-            }).map(|enc| enc.new_decoder_without_bom_handling())?;
+        let decoder = match encoding_name.to_ascii_lowercase().as_str() {
+            "utf-32le" => DecoderImpl::Utf32(Utf32Decoder::new(Utf32ByteOrder::Le)),
+            "utf-32be" => DecoderImpl::Utf32(Utf32Decoder::new(Utf32ByteOrder::Be)),
+            "utf-32" => {
+                // utf-32 with no byte-order suffix is only decodable via its
+                // BOM, so peek it the same way `with_bom_sniffing` does for
+                // UTF-8/UTF-16, then feed the non-BOM bytes into input_buf.
+                let peeked = peek_up_to(&mut inner, 4)?;
+                let (byte_order, bom_len) = match detect_bom(&peeked) {
+                    Some(DetectedBom::Utf32Le) => (Utf32ByteOrder::Le, BOM_UTF32LE.len()),
+                    Some(DetectedBom::Utf32Be) => (Utf32ByteOrder::Be, BOM_UTF32BE.len()),
+                    _ => (Utf32ByteOrder::Be, 0),
+                };
+                input_buf.extend_from_slice(&peeked[bom_len..]);
+                DecoderImpl::Utf32(Utf32Decoder::new(byte_order))
+            }
+            _ => {
+                let decoder =
+                    encoding_rs::Encoding::for_label_no_replacement(encoding_name.as_bytes())
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::Other,
+                                format!("Unrecognized input encoding name: {}", encoding_name),
+                            )
+                        }).map(|enc| enc.new_decoder_without_bom_handling())?;
+                DecoderImpl::EncodingRs(decoder)
+            }
+        };
 
         Ok(CodecReadBuffer {
             inner,
@@ -61,9 +525,41 @@ impl<R: Read> CodecReadBuffer<R> {
             input_buf,
             output_buf: String::new(),
             output_pos: 0,
+            lossy: false,
+            reached_eof: false,
         })
     }
 
+    /// Create a re-encoding buffered reader that never fails on malformed
+    /// input: invalid byte sequences are replaced with U+FFFD instead of
+    /// aborting the stream, matching `encoding_rs`'s lossy decode path.
+    pub fn for_encoding_lossy(inner: R, encoding_name: &str) -> io::Result<Self> {
+        Self::for_encoding_lossy_with_capacity(inner, encoding_name, ::DEFAULT_BUF_SIZE)
+    }
+
+    /// Create a lossy re-encoding buffered reader with the specified buffer capacity
+    pub fn for_encoding_lossy_with_capacity(
+        inner: R,
+        encoding_name: &str,
+        capacity: usize,
+    ) -> io::Result<Self> {
+        Self::for_encoding_lossy_with_initial_buffer(
+            inner,
+            encoding_name,
+            Vec::with_capacity(capacity),
+        )
+    }
+
+    pub fn for_encoding_lossy_with_initial_buffer(
+        inner: R,
+        encoding_name: &str,
+        input_buf: Vec<u8>,
+    ) -> io::Result<Self> {
+        let mut codec_buffer = Self::for_encoding_with_initial_buffer(inner, encoding_name, input_buf)?;
+        codec_buffer.lossy = true;
+        Ok(codec_buffer)
+    }
+
     fn fill_input_buf(&mut self) -> io::Result<usize> {
         if self.input_buf.is_empty() {
             let capacity = self.input_buf.capacity();
@@ -100,15 +596,29 @@ impl<R: Read> BufRead for CodecReadBuffer<R> {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
         if self.output_pos >= self.output_buf.len() {
             debug_assert!(self.output_pos == self.output_buf.len());
-            self.fill_input_buf()?;
+            // The decoder has already been given its final flush call, so
+            // there's nothing left to produce; calling it again would panic.
+            if self.reached_eof {
+                self.output_buf.clear();
+                self.output_pos = 0;
+                return Ok(&[]);
+            }
+            let read_size = self.fill_input_buf()?;
+            // A zero-byte read means the underlying reader is truly exhausted,
+            // so this is our last chance to flush any pending decoder state
+            // (e.g. a truncated multibyte sequence) into output or an error.
+            let last = read_size == 0;
+            self.reached_eof = last;
             // Take raw encoded data and convert it to utf-8
             self.output_buf =
-                decoder_helper(&mut self.decoder, &self.input_buf).map_err(|desc| {
-                    io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Input decoding error: {}", desc),
-                    )
-                })?;
+                decode_step(&mut self.decoder, &self.input_buf, last, self.lossy).map_err(
+                    |desc| {
+                        io::Error::new(
+                            io::ErrorKind::Other,
+                            format!("Input decoding error: {}", desc),
+                        )
+                    },
+                )?;
             self.input_buf.clear();
             self.output_pos = 0;
         }
@@ -250,4 +760,246 @@ mod reader_tests {
             Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
         }
     }
+
+    #[test]
+    fn test_with_bom_sniffing_utf8_bom() {
+        let utf8_validation = include_bytes!("../tests/validation/utf8.xml").to_vec();
+
+        let utf8_with_bom_bytes = include_bytes!("../tests/utf8_bom/doc.xml").to_vec();
+        match CodecReadBuffer::with_bom_sniffing(&utf8_with_bom_bytes as &[u8], "utf-8") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Failed decoding input data");
+                assert_eq!(&utf8_validation, &utf8_encoded_doc.as_bytes());
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_with_bom_sniffing_utf16le_bom() {
+        let utf16_validation = include_bytes!("../tests/validation/utf16le.xml").to_vec();
+
+        let utf16_with_bom_bytes = include_bytes!("../tests/utf16le_bom/doc.xml").to_vec();
+        match CodecReadBuffer::with_bom_sniffing(&utf16_with_bom_bytes as &[u8], "utf-8") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Failed decoding input data");
+                assert_eq!(&utf16_validation, &utf8_encoded_doc.as_bytes());
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_with_bom_sniffing_utf16be_bom() {
+        let utf16_validation = include_bytes!("../tests/validation/utf16be.xml").to_vec();
+
+        let utf16_with_bom_bytes = include_bytes!("../tests/utf16be_bom/doc.xml").to_vec();
+        match CodecReadBuffer::with_bom_sniffing(&utf16_with_bom_bytes as &[u8], "utf-8") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Failed decoding input data");
+                assert_eq!(&utf16_validation, &utf8_encoded_doc.as_bytes());
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_with_bom_sniffing_falls_back_to_default() {
+        // No BOM present, so the caller-supplied default encoding is used
+        let utf8_validation = include_bytes!("../tests/validation/utf8.xml").to_vec();
+
+        let utf8_bytes = include_bytes!("../tests/utf8/doc.xml").to_vec();
+        match CodecReadBuffer::with_bom_sniffing(&utf8_bytes as &[u8], "utf-8") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Failed decoding input data");
+                assert_eq!(&utf8_validation, &utf8_encoded_doc.as_bytes());
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_utf8_truncated_multibyte_char_is_malformed() {
+        // The last character in this fixture is a multibyte utf-8 sequence
+        // with its trailing continuation bytes cut off.
+        let utf8_truncated_bytes = include_bytes!("../tests/utf8_truncated/doc.xml").to_vec();
+        match CodecReadBuffer::for_encoding(&utf8_truncated_bytes as &[u8], "utf-8") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                let result = decoding_reader.read_to_string(&mut utf8_encoded_doc);
+                assert!(
+                    result.is_err(),
+                    "Expected truncated trailing byte sequence to be reported as malformed input"
+                );
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_utf32le() {
+        let utf32_validation = include_bytes!("../tests/validation/utf32le.xml").to_vec();
+
+        let utf32_bytes = include_bytes!("../tests/utf32le/doc.xml").to_vec();
+        match CodecReadBuffer::for_encoding(&utf32_bytes as &[u8], "utf-32le") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Failed decoding input data");
+                assert_eq!(&utf32_validation, &utf8_encoded_doc.as_bytes());
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_utf32be() {
+        let utf32_validation = include_bytes!("../tests/validation/utf32be.xml").to_vec();
+
+        let utf32_bytes = include_bytes!("../tests/utf32be/doc.xml").to_vec();
+        match CodecReadBuffer::for_encoding(&utf32_bytes as &[u8], "utf-32be") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Failed decoding input data");
+                assert_eq!(&utf32_validation, &utf8_encoded_doc.as_bytes());
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_utf32_bom_sniffs_byte_order() {
+        let utf32_validation = include_bytes!("../tests/validation/utf32le.xml").to_vec();
+
+        let utf32_with_bom_bytes = include_bytes!("../tests/utf32le_bom/doc.xml").to_vec();
+        match CodecReadBuffer::for_encoding(&utf32_with_bom_bytes as &[u8], "utf-32") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Failed decoding input data");
+                assert_eq!(&utf32_validation, &utf8_encoded_doc.as_bytes());
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_utf32le_truncated_trailing_bytes_is_malformed() {
+        let utf32_truncated_bytes = include_bytes!("../tests/utf32le_truncated/doc.xml").to_vec();
+        match CodecReadBuffer::for_encoding(&utf32_truncated_bytes as &[u8], "utf-32le") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                let result = decoding_reader.read_to_string(&mut utf8_encoded_doc);
+                assert!(
+                    result.is_err(),
+                    "Expected truncated trailing byte sequence to be reported as malformed input"
+                );
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_utf8_lossy_replaces_malformed_input() {
+        let utf8_truncated_bytes = include_bytes!("../tests/utf8_truncated/doc.xml").to_vec();
+        match CodecReadBuffer::for_encoding_lossy(&utf8_truncated_bytes as &[u8], "utf-8") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Lossy decoding should never fail on malformed input");
+                assert!(utf8_encoded_doc.contains('\u{fffd}'));
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_utf16le_truncated_surrogate_is_malformed() {
+        // The last character in this fixture is a lone UTF-16 high surrogate
+        // with no trailing low surrogate.
+        let utf16_truncated_bytes = include_bytes!("../tests/utf16le_truncated/doc.xml").to_vec();
+        match CodecReadBuffer::for_encoding(&utf16_truncated_bytes as &[u8], "utf-16le") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                let result = decoding_reader.read_to_string(&mut utf8_encoded_doc);
+                assert!(
+                    result.is_err(),
+                    "Expected truncated trailing surrogate to be reported as malformed input"
+                );
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_with_declaration_sniffing_xml_encoding_attr() {
+        // doc.xml declares `encoding="Shift_JIS"` in its prolog, but the
+        // bytes on disk are plain utf-8 for this fixture
+        let utf8_validation = include_bytes!("../tests/validation/utf8.xml").to_vec();
+
+        let declared_bytes = include_bytes!("../tests/utf8_declared_sjis/doc.xml").to_vec();
+        match CodecReadBuffer::with_declaration_sniffing(&declared_bytes as &[u8], "utf-8") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Failed decoding input data");
+                assert_eq!(&utf8_validation, &utf8_encoded_doc.as_bytes());
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_with_declaration_sniffing_bom_takes_precedence() {
+        // BOM says utf-8; a conflicting declared encoding must be ignored
+        let utf8_validation = include_bytes!("../tests/validation/utf8.xml").to_vec();
+
+        let declared_bytes =
+            include_bytes!("../tests/utf8_bom_declared_sjis/doc.xml").to_vec();
+        match CodecReadBuffer::with_declaration_sniffing(&declared_bytes as &[u8], "utf-8") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Failed decoding input data");
+                assert_eq!(&utf8_validation, &utf8_encoded_doc.as_bytes());
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_with_declaration_sniffing_falls_back_to_default() {
+        let utf8_validation = include_bytes!("../tests/validation/utf8.xml").to_vec();
+
+        let utf8_bytes = include_bytes!("../tests/utf8/doc.xml").to_vec();
+        match CodecReadBuffer::with_declaration_sniffing(&utf8_bytes as &[u8], "utf-8") {
+            Ok(mut decoding_reader) => {
+                let mut utf8_encoded_doc: String = String::new();
+                decoding_reader
+                    .read_to_string(&mut utf8_encoded_doc)
+                    .expect("Failed decoding input data");
+                assert_eq!(&utf8_validation, &utf8_encoded_doc.as_bytes());
+            }
+            Err(e) => panic!("Failed initializing CodecReadBuffer: {}", e),
+        }
+    }
 }
@@ -0,0 +1,250 @@
+use std::io;
+use std::io::Write;
+use std::str;
+
+use reader::{BOM_UTF16BE, BOM_UTF16LE, BOM_UTF8};
+
+/// `encoding_rs` has no UTF-16 encoder (it's a decode-only format in the
+/// WHATWG spec), so UTF-16LE/BE are handled by writing code units directly.
+enum Target {
+    EncodingRs(encoding_rs::Encoder),
+    Utf16Le,
+    Utf16Be,
+}
+
+pub struct CodecWriteBuffer<W> {
+    inner: W,
+    target: Target,
+    // utf-8 bytes handed to `write` that don't yet form a complete char
+    pending: Vec<u8>,
+    output_buf: Vec<u8>,
+    write_bom: bool,
+    bom_written: bool,
+}
+
+impl<W: Write> CodecWriteBuffer<W> {
+    /// Create a re-encoding buffered writer that encodes utf-8 input into the specified encoding
+    pub fn for_encoding(inner: W, encoding_name: &str) -> io::Result<Self> {
+        Self::for_encoding_with_capacity(inner, encoding_name, ::DEFAULT_BUF_SIZE)
+    }
+
+    /// Create a re-encoding buffered writer with the specified buffer capacity
+    pub fn for_encoding_with_capacity(
+        inner: W,
+        encoding_name: &str,
+        capacity: usize,
+    ) -> io::Result<Self> {
+        Self::new(inner, encoding_name, capacity, false)
+    }
+
+    /// Create a re-encoding buffered writer that prepends the target encoding's
+    /// byte-order-mark to the output before the first byte is written
+    pub fn for_encoding_with_bom(inner: W, encoding_name: &str) -> io::Result<Self> {
+        Self::for_encoding_with_bom_and_capacity(inner, encoding_name, ::DEFAULT_BUF_SIZE)
+    }
+
+    /// Same as `for_encoding_with_bom`, but with the specified buffer capacity
+    pub fn for_encoding_with_bom_and_capacity(
+        inner: W,
+        encoding_name: &str,
+        capacity: usize,
+    ) -> io::Result<Self> {
+        Self::new(inner, encoding_name, capacity, true)
+    }
+
+    fn new(inner: W, encoding_name: &str, capacity: usize, write_bom: bool) -> io::Result<Self> {
+        let encoding =
+            encoding_rs::Encoding::for_label(encoding_name.as_bytes()).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Unrecognized output encoding name: {}", encoding_name),
+                )
+            })?;
+
+        let target = match encoding.name() {
+            "UTF-16LE" => Target::Utf16Le,
+            "UTF-16BE" => Target::Utf16Be,
+            _ => Target::EncodingRs(encoding.new_encoder()),
+        };
+
+        Ok(CodecWriteBuffer {
+            inner,
+            target,
+            pending: Vec::new(),
+            output_buf: vec![0; capacity],
+            write_bom,
+            bom_written: false,
+        })
+    }
+
+    fn maybe_write_bom(&mut self) -> io::Result<()> {
+        if self.write_bom && !self.bom_written {
+            let bom: &[u8] = match self.target {
+                Target::EncodingRs(ref encoder) => match encoder.encoding().name() {
+                    "UTF-8" => BOM_UTF8,
+                    _ => &[],
+                },
+                Target::Utf16Le => BOM_UTF16LE,
+                Target::Utf16Be => BOM_UTF16BE,
+            };
+            self.inner.write_all(bom)?;
+            self.bom_written = true;
+        }
+        Ok(())
+    }
+
+    /// Encode a complete, validated utf-8 chunk to the target encoding and write it
+    fn encode_chunk(&mut self, chunk: &str, last: bool) -> io::Result<()> {
+        if chunk.is_empty() && !last {
+            return Ok(());
+        }
+        self.maybe_write_bom()?;
+
+        match self.target {
+            Target::EncodingRs(ref mut encoder) => {
+                let mut remaining = chunk;
+                loop {
+                    let (result, bytes_read, bytes_written, _had_replacements) =
+                        encoder.encode_from_utf8(remaining, &mut self.output_buf, last);
+                    self.inner.write_all(&self.output_buf[..bytes_written])?;
+                    remaining = &remaining[bytes_read..];
+                    match result {
+                        encoding_rs::CoderResult::InputEmpty => break,
+                        encoding_rs::CoderResult::OutputFull => continue,
+                    }
+                }
+            }
+            Target::Utf16Le => {
+                for unit in chunk.encode_utf16() {
+                    self.inner.write_all(&unit.to_le_bytes())?;
+                }
+            }
+            Target::Utf16Be => {
+                for unit in chunk.encode_utf16() {
+                    self.inner.write_all(&unit.to_be_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush any buffered state to the target encoding's final form and
+    /// return the wrapped writer. Returns an error if `buf` ends with an
+    /// incomplete utf-8 sequence that was never finished off by a later write.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unterminated utf-8 sequence at end of stream",
+            ));
+        }
+        self.encode_chunk("", true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for CodecWriteBuffer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let input_len = buf.len();
+        self.pending.extend_from_slice(buf);
+
+        let valid_up_to = match str::from_utf8(&self.pending) {
+            Ok(s) => s.len(),
+            Err(e) => {
+                if e.error_len().is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Input is not valid utf-8",
+                    ));
+                }
+                e.valid_up_to()
+            }
+        };
+
+        // `valid_up_to` is guaranteed to land on a char boundary by the
+        // str::from_utf8 check above, so this reparse can't fail. Copy it out
+        // of `self.pending` first so `encode_chunk` is free to mutate `self`.
+        let chunk = str::from_utf8(&self.pending[..valid_up_to]).unwrap().to_owned();
+        self.encode_chunk(&chunk, false)?;
+        self.pending.drain(..valid_up_to);
+
+        Ok(input_len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod writer_tests {
+    use super::*;
+
+    #[test]
+    fn test_utf8_roundtrip() {
+        let mut writer = CodecWriteBuffer::for_encoding(Vec::new(), "utf-8")
+            .expect("Failed initializing CodecWriteBuffer");
+        writer.write_all("hello, world".as_bytes()).unwrap();
+        let out = writer.finish().expect("Failed finishing CodecWriteBuffer");
+        assert_eq!(out, b"hello, world");
+    }
+
+    #[test]
+    fn test_utf16le_roundtrip() {
+        let utf16_validation = include_bytes!("../tests/validation/utf16le.xml").to_vec();
+        let utf16_bytes = include_bytes!("../tests/utf16le/doc.xml").to_vec();
+
+        let mut writer = CodecWriteBuffer::for_encoding(Vec::new(), "utf-16le")
+            .expect("Failed initializing CodecWriteBuffer");
+        writer.write_all(&utf16_validation).unwrap();
+        let out = writer.finish().expect("Failed finishing CodecWriteBuffer");
+        assert_eq!(out, utf16_bytes);
+    }
+
+    #[test]
+    fn test_utf16le_with_bom() {
+        let utf16_validation = include_bytes!("../tests/validation/utf16le.xml").to_vec();
+        let utf16_with_bom_bytes = include_bytes!("../tests/utf16le_bom/doc.xml").to_vec();
+
+        let mut writer = CodecWriteBuffer::for_encoding_with_bom(Vec::new(), "utf-16le")
+            .expect("Failed initializing CodecWriteBuffer");
+        writer.write_all(&utf16_validation).unwrap();
+        let out = writer.finish().expect("Failed finishing CodecWriteBuffer");
+        assert_eq!(out, utf16_with_bom_bytes);
+    }
+
+    #[test]
+    fn test_split_multibyte_char_across_writes() {
+        // The euro sign (E2 82 AC) is split across two write() calls
+        let euro = "\u{20ac}".as_bytes().to_vec();
+
+        let mut writer = CodecWriteBuffer::for_encoding(Vec::new(), "utf-8")
+            .expect("Failed initializing CodecWriteBuffer");
+        writer.write_all(&euro[..1]).unwrap();
+        writer.write_all(&euro[1..]).unwrap();
+        let out = writer.finish().expect("Failed finishing CodecWriteBuffer");
+        assert_eq!(out, euro);
+    }
+
+    #[test]
+    fn test_write_invalid_utf8_is_error() {
+        let mut writer = CodecWriteBuffer::for_encoding(Vec::new(), "utf-8")
+            .expect("Failed initializing CodecWriteBuffer");
+        let err = writer.write(&[0xff, 0xfe]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_finish_with_unterminated_multibyte_char_is_error() {
+        // The euro sign (E2 82 AC) is left truncated after its first byte,
+        // with no follow-up write to complete it.
+        let euro = "\u{20ac}".as_bytes().to_vec();
+
+        let mut writer = CodecWriteBuffer::for_encoding(Vec::new(), "utf-8")
+            .expect("Failed initializing CodecWriteBuffer");
+        writer.write_all(&euro[..1]).unwrap();
+        let err = writer.finish().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}